@@ -0,0 +1,894 @@
+//! Final-hand yaku detection and scoring.
+//!
+//! This mirrors the table/yaku logic of riichi-tools closely enough to produce
+//! training labels: given the winning hand it tries every candidate pair,
+//! decomposes the remaining tiles into sets, scores each resulting partition
+//! (plus the chiitoitsu and kokushi forms), and keeps the highest-value
+//! interpretation. Per pair only the first set decomposition found is scored.
+//! Only the information reachable from an `Event::Hora` plus the reconstructed
+//! `PlayerState` is required, so it can run offline over logged games.
+
+use ndarray::prelude::*;
+
+/// Number of distinct yaku tracked in the multi-hot label, in label order.
+pub const NUM_YAKU: usize = 30;
+
+/// Stable index of each yaku within the multi-hot vector. The order is frozen:
+/// appending new yaku is fine, reordering would invalidate trained heads.
+#[derive(Clone, Copy)]
+#[repr(usize)]
+pub enum Yaku {
+    Riichi = 0,
+    Ippatsu,
+    MenzenTsumo,
+    Pinfu,
+    Tanyao,
+    Iipeikou,
+    Yakuhai,
+    SanshokuDoujun,
+    SanshokuDoukou,
+    Ittsuu,
+    Chanta,
+    Junchan,
+    Toitoi,
+    Sanankou,
+    Sankantsu,
+    Honroutou,
+    Shousangen,
+    Honitsu,
+    Chinitsu,
+    Ryanpeikou,
+    // yakuman
+    KokushiMusou,
+    Suuankou,
+    Daisangen,
+    Shousuushii,
+    Daisuushii,
+    Tsuuiisou,
+    Chinroutou,
+    Ryuuiisou,
+    ChuurenPoutou,
+    Suukantsu,
+}
+
+/// A completed, scored interpretation of a winning hand.
+#[derive(Clone, Debug)]
+pub struct Agari {
+    /// Multi-hot yaku vector of length [`NUM_YAKU`].
+    pub yaku: Array1<bool>,
+    pub han: u8,
+    pub fu: u8,
+    pub score: i32,
+}
+
+/// One called meld, as seen in the log.
+#[derive(Clone, Copy)]
+pub enum Meld {
+    Chi { low: u8 },          // run starting at tile kind `low`
+    Pon { tile: u8 },         // open triplet of `tile`
+    Ankan { tile: u8 },       // concealed kan
+    Minkan { tile: u8 },      // open kan (daiminkan/kakan)
+}
+
+/// Everything needed to score a single win.
+pub struct WinningHand {
+    /// 34-kind counts of the *concealed* tiles, winning tile already merged in.
+    pub tehai: [u8; 34],
+    pub melds: Vec<Meld>,
+    pub winning_tile: u8,
+    pub is_tsumo: bool,
+    pub menzen: bool,
+    pub riichi: bool,
+    pub ippatsu: bool,
+    pub round_wind: u8, // 27..=30
+    pub seat_wind: u8,  // 27..=30
+    pub dora: Vec<u8>,  // indicator kinds
+    pub ura: Vec<u8>,   // indicator kinds (empty when not riichi)
+    /// Number of red fives (aka dora) anywhere in the hand and called melds.
+    pub aka_dora: u8,
+}
+
+/// A fully-decomposed standard hand: four sets plus a pair.
+struct Partition {
+    /// Each set is `(kind, is_triplet)`; a run is identified by its lowest kind.
+    sets: Vec<(u8, bool)>,
+    pair: u8,
+    /// Sets that came from called (open) melds, by index into `sets`.
+    open: Vec<bool>,
+    kans: Vec<(u8, bool)>, // (kind, concealed)
+}
+
+#[inline]
+fn is_honor(kind: u8) -> bool {
+    kind >= 27
+}
+
+#[inline]
+fn is_terminal_or_honor(kind: u8) -> bool {
+    is_honor(kind) || kind % 9 == 0 || kind % 9 == 8
+}
+
+#[inline]
+fn next_dora(indicator: u8) -> u8 {
+    // Indicators may arrive as red fives (34/35/36); fold them to their kind.
+    let indicator = match indicator {
+        34 => 4,
+        35 => 13,
+        36 => 22,
+        k => k,
+    };
+    match indicator {
+        26 | 8 | 17 => indicator - 8,        // 9 man/pin/sou -> 1
+        27..=30 => 27 + (indicator - 27 + 1) % 4, // winds cycle E->S->W->N->E
+        31..=33 => 31 + (indicator - 31 + 1) % 3, // dragons cycle
+        _ => indicator + 1,
+    }
+}
+
+impl WinningHand {
+    /// Score the hand, returning the best interpretation, or `None` when no
+    /// valid decomposition exists (which should not happen for a real win).
+    pub fn best(&self) -> Option<Agari> {
+        let mut best: Option<Agari> = None;
+        let mut consider = |cand: Agari| {
+            if best
+                .as_ref()
+                .map_or(true, |b| (cand.han, cand.fu) > (b.han, b.fu))
+            {
+                best = Some(cand);
+            }
+        };
+
+        if let Some(a) = self.score_kokushi() {
+            consider(a);
+        }
+        if let Some(a) = self.score_chiitoitsu() {
+            consider(a);
+        }
+        for part in self.decompose() {
+            consider(self.score_partition(&part));
+        }
+        best
+    }
+
+    /// Build standard 4-set + pair partitions: for each candidate pair, the
+    /// remaining concealed tiles are split into sets and joined with the called
+    /// melds. Only the first split found per pair (triplets tried before runs)
+    /// is returned; other arrangements with the same pair are not enumerated.
+    fn decompose(&self) -> Vec<Partition> {
+        let mut out = vec![];
+        let hand = self.tehai;
+
+        // `tehai` holds only the concealed tiles; called melds map straight to sets.
+        let called: Vec<(u8, bool)> = self
+            .melds
+            .iter()
+            .map(|m| match *m {
+                Meld::Chi { low } => {
+                    (low, false)
+                }
+                Meld::Pon { tile } => {
+                    (tile, true)
+                }
+                Meld::Ankan { tile } | Meld::Minkan { tile } => {
+                    (tile, true)
+                }
+            })
+            .collect();
+
+        for pair in 0..34u8 {
+            if hand[pair as usize] < 2 {
+                continue;
+            }
+            let mut rest = hand;
+            rest[pair as usize] -= 2;
+            let mut sets = vec![];
+            if decompose_sets(&mut rest, 0, &mut sets) {
+                let mut all = called.clone();
+                // Called melds are open, except a concealed kan (ankan).
+                let mut open: Vec<bool> = self
+                    .melds
+                    .iter()
+                    .map(|m| !matches!(m, Meld::Ankan { .. }))
+                    .collect();
+                all.extend_from_slice(&sets);
+                open.extend(std::iter::repeat(false).take(sets.len()));
+                let kans = self
+                    .melds
+                    .iter()
+                    .filter_map(|m| match *m {
+                        Meld::Ankan { tile } => Some((tile, true)),
+                        Meld::Minkan { tile } => Some((tile, false)),
+                        _ => None,
+                    })
+                    .collect();
+                out.push(Partition {
+                    sets: all,
+                    pair,
+                    open,
+                    kans,
+                });
+            }
+        }
+        out
+    }
+
+    fn score_partition(&self, part: &Partition) -> Agari {
+        let mut yaku = Array1::from_elem(NUM_YAKU, false);
+        let mut set = |y: Yaku| yaku[y as usize] = true;
+
+        let triplets: Vec<u8> = part.sets.iter().filter(|s| s.1).map(|s| s.0).collect();
+        let runs: Vec<u8> = part.sets.iter().filter(|s| !s.1).map(|s| s.0).collect();
+        let all_kinds = || {
+            part.sets
+                .iter()
+                .flat_map(|&(k, t)| {
+                    if t {
+                        vec![k, k, k]
+                    } else {
+                        vec![k, k + 1, k + 2]
+                    }
+                })
+                .chain([part.pair, part.pair])
+        };
+
+        if self.riichi {
+            set(Yaku::Riichi);
+            if self.ippatsu {
+                set(Yaku::Ippatsu);
+            }
+        }
+        if self.menzen && self.is_tsumo {
+            set(Yaku::MenzenTsumo);
+        }
+
+        // tanyao: no terminals or honors anywhere.
+        if all_kinds().all(|k| !is_terminal_or_honor(k)) {
+            set(Yaku::Tanyao);
+        }
+
+        // yakuhai: one han per value triplet. A double wind (round == seat)
+        // contributes two, so count each qualifying triplet separately.
+        let mut yakuhai_count = 0u8;
+        for &t in &triplets {
+            if (31..=33).contains(&t) {
+                yakuhai_count += 1;
+            }
+            if t == self.round_wind {
+                yakuhai_count += 1;
+            }
+            if t == self.seat_wind {
+                yakuhai_count += 1;
+            }
+        }
+        if yakuhai_count > 0 {
+            set(Yaku::Yakuhai);
+        }
+
+        // pinfu: menzen, all runs, non-yakuhai pair, ryanmen wait.
+        if self.menzen
+            && triplets.is_empty()
+            && part.kans.is_empty()
+            && !(31..=33).contains(&part.pair)
+            && part.pair != self.round_wind
+            && part.pair != self.seat_wind
+            && self.is_ryanmen_wait(&runs)
+        {
+            set(Yaku::Pinfu);
+        }
+
+        // iipeikou / ryanpeikou (menzen only).
+        if self.menzen {
+            let mut run_counts = [0u8; 34];
+            for &r in &runs {
+                run_counts[r as usize] += 1;
+            }
+            let pairs = run_counts.iter().filter(|&&c| c >= 2).count();
+            if pairs >= 2 {
+                set(Yaku::Ryanpeikou);
+            } else if pairs == 1 {
+                set(Yaku::Iipeikou);
+            }
+        }
+
+        if runs.len() >= 3 {
+            // sanshoku doujun: same run in all three number suits.
+            for low in 0..7u8 {
+                if runs.contains(&low) && runs.contains(&(low + 9)) && runs.contains(&(low + 18)) {
+                    set(Yaku::SanshokuDoujun);
+                }
+            }
+            // ittsuu: 123/456/789 in one suit.
+            for suit in 0..3u8 {
+                let base = suit * 9;
+                if runs.contains(&base) && runs.contains(&(base + 3)) && runs.contains(&(base + 6))
+                {
+                    set(Yaku::Ittsuu);
+                }
+            }
+        }
+
+        // sanshoku doukou: same triplet in all three suits.
+        for t in 0..9u8 {
+            if triplets.contains(&t) && triplets.contains(&(t + 9)) && triplets.contains(&(t + 18))
+            {
+                set(Yaku::SanshokuDoukou);
+            }
+        }
+
+        // toitoi / san'ankou / suuankou.
+        if runs.is_empty() {
+            set(Yaku::Toitoi);
+        }
+        let mut concealed_triplets = part
+            .sets
+            .iter()
+            .zip(&part.open)
+            .filter(|((_, is_trip), open)| *is_trip && !**open)
+            .count();
+        // A triplet completed by the ron tile is a minko (open triplet), so it
+        // does not count towards san'ankou / suuankou.
+        if !self.is_tsumo
+            && part
+                .sets
+                .iter()
+                .zip(&part.open)
+                .any(|((k, is_trip), open)| *is_trip && !*open && *k == self.winning_tile)
+        {
+            concealed_triplets = concealed_triplets.saturating_sub(1);
+        }
+        if concealed_triplets == 4 {
+            set(Yaku::Suuankou);
+        } else if concealed_triplets == 3 {
+            set(Yaku::Sanankou);
+        }
+
+        // kan-based.
+        let kans = part.kans.len();
+        if kans == 4 {
+            set(Yaku::Suukantsu);
+        } else if kans == 3 {
+            set(Yaku::Sankantsu);
+        }
+
+        // chanta / junchan: every set and the pair contains a terminal/honor.
+        let each_set_has_edge = part.sets.iter().all(|&(k, t)| {
+            if t {
+                is_terminal_or_honor(k)
+            } else {
+                k % 9 == 0 || k % 9 == 6
+            }
+        }) && is_terminal_or_honor(part.pair);
+        if each_set_has_edge {
+            if all_kinds().all(|k| !is_honor(k)) {
+                set(Yaku::Junchan);
+            } else {
+                set(Yaku::Chanta);
+            }
+        }
+
+        // honroutou: only terminals and honors.
+        if all_kinds().all(is_terminal_or_honor) {
+            set(Yaku::Honroutou);
+            if all_kinds().all(|k| !is_honor(k)) {
+                set(Yaku::Chinroutou);
+            }
+            if all_kinds().all(is_honor) {
+                set(Yaku::Tsuuiisou);
+            }
+        }
+
+        // dragon yaku.
+        let dragon_triplets = triplets.iter().filter(|&&t| (31..=33).contains(&t)).count();
+        let dragon_pair = (31..=33).contains(&part.pair);
+        if dragon_triplets == 3 {
+            set(Yaku::Daisangen);
+        } else if dragon_triplets == 2 && dragon_pair {
+            set(Yaku::Shousangen);
+        }
+
+        // wind yaku.
+        let wind_triplets = triplets.iter().filter(|&&t| (27..=30).contains(&t)).count();
+        let wind_pair = (27..=30).contains(&part.pair);
+        if wind_triplets == 4 {
+            set(Yaku::Daisuushii);
+        } else if wind_triplets == 3 && wind_pair {
+            set(Yaku::Shousuushii);
+        }
+
+        // flush yaku.
+        let suits: Vec<u8> = all_kinds().filter(|&k| !is_honor(k)).map(|k| k / 9).collect();
+        let single_suit = suits.windows(2).all(|w| w[0] == w[1]);
+        if single_suit && !suits.is_empty() {
+            if all_kinds().all(|k| !is_honor(k)) {
+                set(Yaku::Chinitsu);
+            } else {
+                set(Yaku::Honitsu);
+            }
+        }
+
+        // ryuuiisou: all-green tiles.
+        const GREEN: [u8; 6] = [19, 20, 21, 23, 25, 32]; // s2 s3 s4 s6 s8 hatsu
+        if all_kinds().all(|k| GREEN.contains(&k)) {
+            set(Yaku::Ryuuiisou);
+        }
+
+        // chuuren poutou: 1112345678999 + any in one suit, menzen.
+        if self.menzen && single_suit && all_kinds().all(|k| !is_honor(k)) {
+            if let Some(suit) = suits.first() {
+                let base = suit * 9;
+                let mut counts = [0u8; 9];
+                for k in all_kinds() {
+                    counts[(k - base) as usize] += 1;
+                }
+                let chuuren = (0..9).all(|i| {
+                    let need = if i == 0 || i == 8 { 3 } else { 1 };
+                    counts[i] >= need
+                });
+                if chuuren {
+                    set(Yaku::ChuurenPoutou);
+                }
+            }
+        }
+
+        let pinfu = yaku[Yaku::Pinfu as usize];
+        let fu = self.calc_fu(part, &triplets, pinfu);
+        let (han, dora_han) = self.count_han(&yaku, part);
+        // `count_han` scores the yakuhai flag as a single han; add the rest.
+        let extra_yakuhai = yakuhai_count.saturating_sub(1);
+        self.finish(yaku, han + dora_han + extra_yakuhai, fu)
+    }
+
+    fn is_ryanmen_wait(&self, runs: &[u8]) -> bool {
+        // The winning tile completes a two-sided run and is not its middle.
+        let w = self.winning_tile;
+        runs.iter().any(|&low| {
+            (w == low && low % 9 != 6) || (w == low + 2 && low % 9 != 0)
+        })
+    }
+
+    fn calc_fu(&self, part: &Partition, triplets: &[u8], pinfu: bool) -> u8 {
+        // Pinfu fixes fu to 20 on tsumo and 30 on menzen ron; the usual bonuses
+        // (tsumo, wait, triplet, pair) are all zero by construction.
+        if pinfu {
+            return if self.is_tsumo { 20 } else { 30 };
+        }
+        let mut fu = 20;
+        if self.menzen && !self.is_tsumo {
+            fu += 10; // menzen ron
+        }
+        if self.is_tsumo {
+            fu += 2;
+        }
+        // pair fu for yakuhai pairs.
+        if (31..=33).contains(&part.pair) {
+            fu += 2;
+        }
+        if part.pair == self.round_wind {
+            fu += 2;
+        }
+        if part.pair == self.seat_wind {
+            fu += 2;
+        }
+        // triplet / kan fu.
+        for (&t, (_, open)) in triplets.iter().zip(
+            part.sets
+                .iter()
+                .zip(&part.open)
+                .filter(|((_, is_trip), _)| *is_trip)
+                .map(|(s, o)| (s, o)),
+        ) {
+            let edge = is_terminal_or_honor(t);
+            let is_kan = part.kans.iter().any(|&(k, _)| k == t);
+            let concealed_kan = part.kans.iter().any(|&(k, c)| k == t && c);
+            // A triplet finished by the ron tile scores as a minko, not an anko.
+            let ron_completed = !self.is_tsumo && !*open && !is_kan && t == self.winning_tile;
+            let base = match (is_kan, concealed_kan) {
+                (true, true) => 32,
+                (true, false) => 16,
+                (false, _) if *open || ron_completed => 2,
+                (false, _) => 4, // concealed triplet
+            };
+            fu += if edge { base * 2 } else { base };
+        }
+        // wait fu: kanchan/penchan/tanki add 2; ryanmen and shanpon add 0
+        // (a shanpon's value is already in the completed triplet's fu).
+        let runs: Vec<u8> = part.sets.iter().filter(|s| !s.1).map(|s| s.0).collect();
+        let is_shanpon = triplets.contains(&self.winning_tile);
+        if !self.is_ryanmen_wait(&runs) && !is_shanpon {
+            fu += 2;
+        }
+        // round up to the next 10.
+        ((fu + 9) / 10) * 10
+    }
+
+    fn count_han(&self, yaku: &Array1<bool>, part: &Partition) -> (u8, u8) {
+        const OPEN_DISCOUNT: [(usize, u8); 6] = [
+            (Yaku::SanshokuDoujun as usize, 1),
+            (Yaku::Ittsuu as usize, 1),
+            (Yaku::Chanta as usize, 1),
+            (Yaku::Junchan as usize, 1),
+            (Yaku::Honitsu as usize, 1),
+            (Yaku::Chinitsu as usize, 1),
+        ];
+        let base: [(usize, u8); 20] = [
+            (Yaku::Riichi as usize, 1),
+            (Yaku::Ippatsu as usize, 1),
+            (Yaku::MenzenTsumo as usize, 1),
+            (Yaku::Pinfu as usize, 1),
+            (Yaku::Tanyao as usize, 1),
+            (Yaku::Iipeikou as usize, 1),
+            (Yaku::Yakuhai as usize, 1),
+            (Yaku::SanshokuDoujun as usize, 2),
+            (Yaku::SanshokuDoukou as usize, 2),
+            (Yaku::Ittsuu as usize, 2),
+            (Yaku::Chanta as usize, 2),
+            (Yaku::Junchan as usize, 3),
+            (Yaku::Toitoi as usize, 2),
+            (Yaku::Sanankou as usize, 2),
+            (Yaku::Sankantsu as usize, 2),
+            (Yaku::Honroutou as usize, 2),
+            (Yaku::Shousangen as usize, 2),
+            (Yaku::Honitsu as usize, 3),
+            (Yaku::Chinitsu as usize, 6),
+            (Yaku::Ryanpeikou as usize, 3),
+        ];
+        let open = part.open.iter().any(|&o| o);
+        let mut han = 0;
+        for &(idx, value) in &base {
+            if yaku[idx] {
+                let discount = if open {
+                    OPEN_DISCOUNT
+                        .iter()
+                        .find(|&&(i, _)| i == idx)
+                        .map_or(0, |&(_, d)| d)
+                } else {
+                    0
+                };
+                han += value - discount;
+            }
+        }
+
+        // dora: indicators point to the next tile; ura only counts under riichi.
+        let mut dora_han = 0u8;
+        let mut counts = [0u8; 34];
+        for (k, t) in part.sets.iter() {
+            if *t {
+                counts[*k as usize] += if part.kans.iter().any(|&(kk, _)| kk == *k) {
+                    4
+                } else {
+                    3
+                };
+            } else {
+                for o in 0..3u8 {
+                    counts[(*k + o) as usize] += 1;
+                }
+            }
+        }
+        counts[part.pair as usize] += 2;
+        for &ind in &self.dora {
+            dora_han += counts[next_dora(ind) as usize];
+        }
+        if self.riichi {
+            for &ind in &self.ura {
+                dora_han += counts[next_dora(ind) as usize];
+            }
+        }
+        dora_han += self.aka_dora;
+        (han, dora_han)
+    }
+
+    fn score_chiitoitsu(&self) -> Option<Agari> {
+        let pairs = self.tehai.iter().filter(|&&c| c == 2).count();
+        if pairs != 7 || !self.melds.is_empty() || !self.menzen {
+            return None;
+        }
+        let mut yaku = Array1::from_elem(NUM_YAKU, false);
+        if self.riichi {
+            yaku[Yaku::Riichi as usize] = true;
+            if self.ippatsu {
+                yaku[Yaku::Ippatsu as usize] = true;
+            }
+        }
+        if self.is_tsumo {
+            yaku[Yaku::MenzenTsumo as usize] = true;
+        }
+        let kinds = || (0..34u8).filter(|&k| self.tehai[k as usize] == 2);
+        if kinds().all(|k| !is_terminal_or_honor(k)) {
+            yaku[Yaku::Tanyao as usize] = true;
+        }
+        if kinds().all(is_terminal_or_honor) {
+            yaku[Yaku::Honroutou as usize] = true;
+        }
+        let suits: Vec<u8> = kinds().filter(|&k| !is_honor(k)).map(|k| k / 9).collect();
+        if suits.windows(2).all(|w| w[0] == w[1]) && !suits.is_empty() {
+            if kinds().all(|k| !is_honor(k)) {
+                yaku[Yaku::Chinitsu as usize] = true;
+            } else {
+                yaku[Yaku::Honitsu as usize] = true;
+            }
+        }
+        let mut han = 2; // chiitoitsu itself; the list below adds the other yaku
+        for idx in [
+            Yaku::Riichi as usize,
+            Yaku::Ippatsu as usize,
+            Yaku::MenzenTsumo as usize,
+            Yaku::Tanyao as usize,
+            Yaku::Honroutou as usize,
+        ] {
+            if yaku[idx] {
+                han += 1;
+            }
+        }
+        if yaku[Yaku::Chinitsu as usize] {
+            han += 6;
+        } else if yaku[Yaku::Honitsu as usize] {
+            han += 3;
+        }
+        let mut counts = self.tehai;
+        let mut dora = 0;
+        for &ind in &self.dora {
+            dora += counts[next_dora(ind) as usize];
+        }
+        if self.riichi {
+            for &ind in &self.ura {
+                dora += counts[next_dora(ind) as usize];
+            }
+        }
+        dora += self.aka_dora;
+        Some(self.finish(yaku, han + dora, 25))
+    }
+
+    fn score_kokushi(&self) -> Option<Agari> {
+        let singles = (0..34u8).filter(|&k| is_terminal_or_honor(k));
+        if !self.melds.is_empty() {
+            return None;
+        }
+        if !singles.clone().all(|k| self.tehai[k as usize] >= 1) {
+            return None;
+        }
+        if (0..34).any(|k| !is_terminal_or_honor(k) && self.tehai[k as usize] > 0) {
+            return None;
+        }
+        let mut yaku = Array1::from_elem(NUM_YAKU, false);
+        yaku[Yaku::KokushiMusou as usize] = true;
+        Some(self.finish(yaku, 13, 0))
+    }
+
+    /// Assemble the final [`Agari`], folding yakuman into a fixed han bucket and
+    /// computing the payout from han/fu.
+    fn finish(&self, yaku: Array1<bool>, han: u8, fu: u8) -> Agari {
+        let yakuman = (Yaku::KokushiMusou as usize..NUM_YAKU)
+            .filter(|&i| yaku[i])
+            .count() as u8;
+        let is_dealer = self.seat_wind == 27;
+        let (han, score) = if yakuman > 0 {
+            let per = if is_dealer { 48000 } else { 32000 };
+            (13 * yakuman, per * yakuman as i32)
+        } else {
+            (han, score_from(han, fu, is_dealer, self.is_tsumo))
+        };
+        Agari {
+            yaku,
+            han,
+            fu,
+            score,
+        }
+    }
+}
+
+/// Recursively peel triplets and runs off `rest`, lowest kind first, stopping
+/// at the first complete decomposition.
+fn decompose_sets(rest: &mut [u8; 34], start: u8, sets: &mut Vec<(u8, bool)>) -> bool {
+    let Some(kind) = (start..34).find(|&k| rest[k as usize] > 0) else {
+        return true;
+    };
+    let k = kind as usize;
+    if rest[k] >= 3 {
+        rest[k] -= 3;
+        sets.push((kind, true));
+        if decompose_sets(rest, kind, sets) {
+            return true;
+        }
+        sets.pop();
+        rest[k] += 3;
+    }
+    if !is_honor(kind) && kind % 9 <= 6 && rest[k + 1] > 0 && rest[k + 2] > 0 {
+        rest[k] -= 1;
+        rest[k + 1] -= 1;
+        rest[k + 2] -= 1;
+        sets.push((kind, false));
+        if decompose_sets(rest, kind, sets) {
+            return true;
+        }
+        sets.pop();
+        rest[k] += 1;
+        rest[k + 1] += 1;
+        rest[k + 2] += 1;
+    }
+    false
+}
+
+/// Total non-yakuman payout from han/fu, honouring dealer and tsumo splitting.
+///
+/// The mangan cap applies only to the 1..=4 han formula; the explicit
+/// 5+ han buckets (mangan through kazoe-yakuman) are already final base values.
+fn score_from(han: u8, fu: u8, is_dealer: bool, is_tsumo: bool) -> i32 {
+    let base = match han {
+        0 => return 0,
+        1..=4 => ((fu as i32) * (1 << (2 + han))).min(2000),
+        5 => 2000,
+        6 | 7 => 3000,
+        8..=10 => 4000,
+        11 | 12 => 6000,
+        _ => 8000,
+    };
+    // Each payment is rounded up to the next 100 independently.
+    let ceil100 = |x: i32| ((x + 99) / 100) * 100;
+    match (is_dealer, is_tsumo) {
+        (false, false) => ceil100(base * 4),
+        (false, true) => ceil100(base * 2) + 2 * ceil100(base),
+        (true, false) => ceil100(base * 6),
+        (true, true) => 3 * ceil100(base * 2),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build a purely concealed, non-riichi hand from 34-kind counts.
+    fn hand(tehai: [u8; 34], winning_tile: u8, is_tsumo: bool) -> WinningHand {
+        WinningHand {
+            tehai,
+            melds: vec![],
+            winning_tile,
+            is_tsumo,
+            menzen: true,
+            riichi: false,
+            ippatsu: false,
+            round_wind: 27,
+            seat_wind: 28, // south: non-dealer
+            dora: vec![],
+            ura: vec![],
+            aka_dora: 0,
+        }
+    }
+
+    #[test]
+    fn score_from_does_not_cap_above_mangan() {
+        // 4 han 30 fu is a genuine mangan-ceiling payout.
+        assert_eq!(score_from(4, 30, false, false), 7700);
+        // The explicit 5+ han buckets must not be clamped to the mangan base.
+        assert_eq!(score_from(5, 40, false, false), 8000); // mangan
+        assert_eq!(score_from(6, 40, false, false), 12000); // haneman
+        assert_eq!(score_from(8, 40, false, false), 16000); // baiman
+        assert_eq!(score_from(11, 40, false, false), 24000); // sanbaiman
+        assert_eq!(score_from(13, 40, false, false), 32000); // kazoe yakuman
+    }
+
+    #[test]
+    fn score_from_dealer_and_tsumo() {
+        // Dealer mangan ron is 12000; dealer mangan tsumo is 4000 all.
+        assert_eq!(score_from(5, 40, true, false), 12000);
+        assert_eq!(score_from(5, 40, true, true), 12000);
+        // Non-dealer mangan tsumo: 2000/4000 split -> 8000 total.
+        assert_eq!(score_from(5, 40, false, true), 8000);
+    }
+
+    #[test]
+    fn pinfu_tsumo_is_twenty_fu() {
+        // 456m 789m 234p 567p 33s, tsumo on 4m (ryanmen).
+        let mut t = [0u8; 34];
+        for k in [3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15] {
+            t[k] += 1;
+        }
+        t[20] += 2; // 3s pair
+        let agari = hand(t, 3, true).best().unwrap();
+        assert!(agari.yaku[Yaku::Pinfu as usize]);
+        assert!(agari.yaku[Yaku::MenzenTsumo as usize]);
+        assert_eq!(agari.fu, 20);
+    }
+
+    #[test]
+    fn ron_completed_triplet_is_not_suuankou() {
+        // 111m 222m 333m 444m 55m, ron on 4m completes the last triplet.
+        let mut t = [0u8; 34];
+        for k in [0, 1, 2, 3] {
+            t[k] = 3;
+        }
+        t[4] = 2;
+        let agari = hand(t, 3, false).best().unwrap();
+        assert!(!agari.yaku[Yaku::Suuankou as usize]);
+        assert!(agari.yaku[Yaku::Sanankou as usize]);
+    }
+
+    #[test]
+    fn aka_dora_adds_han() {
+        // 456m 789m 234p 567p 33s tsumo, but with one red five held.
+        let mut t = [0u8; 34];
+        for k in [3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15] {
+            t[k] += 1;
+        }
+        t[20] += 2;
+        let mut h = hand(t, 3, true);
+        let without = h.best().unwrap().han;
+        h.aka_dora = 1;
+        assert_eq!(h.best().unwrap().han, without + 1);
+    }
+
+    #[test]
+    fn double_wind_counts_twice() {
+        // Dealer East round: East triplet is both round and seat wind.
+        let mut t = [0u8; 34];
+        t[27] = 3; // east triplet
+        for k in [1, 2, 3, 12, 13, 14, 15, 16, 17] {
+            t[k] += 1; // 234m, 456p, 789p
+        }
+        t[18] += 2; // 1s pair
+        let mut h = hand(t, 1, true);
+        h.round_wind = 27;
+        h.seat_wind = 27; // dealer east
+        let agari = h.best().unwrap();
+        assert!(agari.yaku[Yaku::Yakuhai as usize]);
+        // menzen tsumo (1) + double east (2).
+        assert_eq!(agari.han, 3);
+    }
+
+    #[test]
+    fn shanpon_wait_adds_no_fu() {
+        // 222m 333m 456p 789p 11s, menzen tsumo completing 2m by shanpon.
+        let mut t = [0u8; 34];
+        t[1] = 3; // 222m (completed by tsumo)
+        t[2] = 3; // 333m
+        for k in [12, 13, 14, 15, 16, 17] {
+            t[k] += 1; // 456p 789p
+        }
+        t[18] += 2; // 1s pair
+        let agari = hand(t, 1, true).best().unwrap();
+        // 20 base + 2 tsumo + two simple ankou (4+4) = 30, no shanpon wait fu.
+        assert_eq!(agari.fu, 30);
+    }
+
+    #[test]
+    fn closed_chinitsu_with_ankan_is_six_han() {
+        // All sou, menzen, one concealed kan; closed chinitsu must stay 6 han.
+        let mut t = [0u8; 34];
+        for k in [19, 20, 21, 22, 23, 24] {
+            t[k] += 1; // 234s 567s
+        }
+        t[25] = 3; // 888s triplet
+        t[26] = 2; // 9s pair
+        let h = WinningHand {
+            tehai: t,
+            melds: vec![Meld::Ankan { tile: 18 }], // concealed kan of 1s
+            winning_tile: 19,
+            is_tsumo: false,
+            menzen: true,
+            riichi: false,
+            ippatsu: false,
+            round_wind: 27,
+            seat_wind: 28,
+            dora: vec![],
+            ura: vec![],
+            aka_dora: 0,
+        };
+        let agari = h.best().unwrap();
+        assert!(agari.yaku[Yaku::Chinitsu as usize]);
+        assert_eq!(agari.han, 6);
+    }
+
+    #[test]
+    fn red_five_indicator_does_not_panic() {
+        // A red-five dora indicator (kind 34) must fold to kind 4, not index 35.
+        assert_eq!(next_dora(34), 5);
+        let mut t = [0u8; 34];
+        for k in [3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15] {
+            t[k] += 1;
+        }
+        t[20] += 2;
+        let mut h = hand(t, 3, true);
+        h.dora = vec![34];
+        let _ = h.best().unwrap();
+    }
+}
@@ -1,17 +1,24 @@
+use super::agari::{self, WinningHand};
 use super::invisible::Invisible;
+use super::tenhou;
 use super::Grp;
+use crate::algo::shanten;
 use crate::chi_type::ChiType;
 use crate::mjai::Event;
 use crate::state::PlayerState;
-use std::fs::File;
+use crate::tile::Tile;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::{bail, Context, Result};
 use boomphf::hashmap::BoomHashMap;
 use derivative::Derivative;
 use flate2::read::GzDecoder;
 use ndarray::prelude::*;
+use ndarray_npy::WriteNpyExt;
 use numpy::{PyArray1, PyArray2};
 use pyo3::prelude::*;
 use rayon::prelude::*;
@@ -63,6 +70,13 @@ pub struct Gameplay {
     pub apply_gamma: Vec<bool>,
     pub at_turns: Vec<u8>,
     pub shantens: Vec<i8>,
+    pub ukeire: Vec<u16>,
+
+    // one per kyoku the POV player won
+    pub agari_yaku: Vec<Array1<bool>>,
+    pub agari_han: Vec<u8>,
+    pub agari_fu: Vec<u8>,
+    pub agari_score: Vec<i32>,
 
     // one per game
     pub grp: Grp, // actually per kyoku
@@ -141,6 +155,30 @@ impl GameplayLoader {
         self.load_gz_log_files(gzip_filenames)
     }
 
+    // Tenhou logs are a single XML-ish document per game, not line-delimited.
+    #[pyo3(text_signature = "($self, raw, /)")]
+    fn load_tenhou_log(&self, raw: &str) -> Result<Vec<Gameplay>> {
+        let events = tenhou::tenhou_to_mjai(raw).context("failed to parse tenhou log")?;
+        self.load_events(&events)
+    }
+
+    #[pyo3(name = "load_gz_tenhou_log_files")]
+    #[pyo3(text_signature = "($self, gzip_filenames, /)")]
+    fn load_gz_tenhou_log_files_py(&self, gzip_filenames: Vec<String>) -> Result<Vec<Gameplay>> {
+        self.load_gz_tenhou_log_files(gzip_filenames)
+    }
+
+    #[pyo3(name = "load_gz_log_files_to_npz")]
+    #[pyo3(text_signature = "($self, gzip_filenames, out_dir, shard_size, /)")]
+    fn load_gz_log_files_to_npz_py(
+        &self,
+        gzip_filenames: Vec<String>,
+        out_dir: String,
+        shard_size: usize,
+    ) -> Result<Vec<ShardMeta>> {
+        self.load_gz_log_files_to_npz(gzip_filenames, &out_dir, shard_size)
+    }
+
     fn __repr__(&self) -> String {
         format!("{self:?}")
     }
@@ -169,6 +207,89 @@ impl GameplayLoader {
         Ok(res?.into_iter().flatten().collect())
     }
 
+    pub fn load_gz_tenhou_log_files<V, S>(&self, gzip_filenames: V) -> Result<Vec<Gameplay>>
+    where
+        V: IntoParallelIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let res: Result<Vec<Vec<_>>> = gzip_filenames
+            .into_par_iter()
+            .map(|f| {
+                let filename = f.as_ref();
+                let inner = || {
+                    let file = File::open(filename)?;
+                    let mut gz = GzDecoder::new(file);
+                    let mut raw = String::new();
+                    gz.read_to_string(&mut raw)?;
+                    self.load_tenhou_log(&raw)
+                };
+                inner().with_context(|| format!("error when reading {filename}"))
+            })
+            .collect();
+        Ok(res?.into_iter().flatten().collect())
+    }
+
+    /// Out-of-core variant of [`load_gz_log_files`](Self::load_gz_log_files):
+    /// instead of returning every move's tensors, append them to sharded `.npy`
+    /// files under `out_dir` and return only per-shard metadata. A shard holds
+    /// whole games, so it is cut at the first game boundary at or past
+    /// `shard_size` rows; each worker's last shard may be smaller.
+    ///
+    /// Files are processed in parallel; each rayon worker keeps a thread-local
+    /// [`ShardWriter`] and flushes it once it crosses `shard_size` rows, so peak
+    /// memory stays O(`shard_size`) regardless of corpus size. Shard filenames
+    /// are made unique through a shared atomic counter. The per-window encoding
+    /// is the very same [`load_events`](Self::load_events) path the in-memory
+    /// loader uses, so the two sinks cannot drift apart.
+    pub fn load_gz_log_files_to_npz<V, S>(
+        &self,
+        gzip_filenames: V,
+        out_dir: &str,
+        shard_size: usize,
+    ) -> Result<Vec<ShardMeta>>
+    where
+        V: IntoParallelIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        ensure_dir(out_dir)?;
+        let shard_counter = AtomicUsize::new(0);
+
+        gzip_filenames
+            .into_par_iter()
+            .fold(
+                || Ok(ShardWriter::new(self.oracle)),
+                |acc: Result<ShardWriter>, f| {
+                    let mut writer = acc?;
+                    let filename = f.as_ref();
+                    let games = (|| {
+                        let file = File::open(filename)?;
+                        let mut gz = GzDecoder::new(file);
+                        let mut raw = String::new();
+                        gz.read_to_string(&mut raw)?;
+                        self.load_log(&raw)
+                    })()
+                    .with_context(|| format!("error when reading {filename}"))?;
+                    for game in games {
+                        writer.append(game, &shard_counter, out_dir, shard_size)?;
+                    }
+                    Ok(writer)
+                },
+            )
+            .map(|acc: Result<ShardWriter>| {
+                let mut writer = acc?;
+                writer.finish(&shard_counter, out_dir)?;
+                Ok(writer.metas)
+            })
+            .reduce(
+                || Ok(vec![]),
+                |a, b| {
+                    let mut a = a?;
+                    a.extend(b?);
+                    Ok(a)
+                },
+            )
+    }
+
     pub fn load_events(&self, events: &[Event]) -> Result<Vec<Gameplay>> {
         let invisibles = self.oracle.then(|| Invisible::new(events, self.trust_seed));
 
@@ -245,6 +366,30 @@ impl Gameplay {
     fn take_shantens(&mut self) -> Vec<i8> {
         mem::take(&mut self.shantens)
     }
+    #[pyo3(text_signature = "($self, /)")]
+    fn take_ukeire(&mut self) -> Vec<u16> {
+        mem::take(&mut self.ukeire)
+    }
+
+    #[pyo3(text_signature = "($self, /)")]
+    fn take_agari_yaku<'py>(&mut self, py: Python<'py>) -> Vec<&'py PyArray1<bool>> {
+        mem::take(&mut self.agari_yaku)
+            .into_iter()
+            .map(|v| PyArray1::from_owned_array(py, v))
+            .collect()
+    }
+    #[pyo3(text_signature = "($self, /)")]
+    fn take_agari_han(&mut self) -> Vec<u8> {
+        mem::take(&mut self.agari_han)
+    }
+    #[pyo3(text_signature = "($self, /)")]
+    fn take_agari_fu(&mut self) -> Vec<u8> {
+        mem::take(&mut self.agari_fu)
+    }
+    #[pyo3(text_signature = "($self, /)")]
+    fn take_agari_score(&mut self) -> Vec<i32> {
+        mem::take(&mut self.agari_score)
+    }
 
     #[pyo3(text_signature = "($self, /)")]
     fn take_grp(&mut self) -> Grp {
@@ -357,6 +502,23 @@ impl Gameplay {
         }
 
         let cans = state.update(cur);
+
+        // Score the realized hand whenever the POV player declares a win. This
+        // runs before the `can_act` gate because a Hora is not an actionable
+        // decision point but still carries a per-kyoku label.
+        if let Event::Hora { actor, target, pai, .. } = *cur {
+            if actor == self.player_id {
+                if let Some(hand) = build_winning_hand(state, actor == target, pai) {
+                    if let Some(agari) = hand.best() {
+                        self.agari_yaku.push(agari.yaku);
+                        self.agari_han.push(agari.han);
+                        self.agari_fu.push(agari.fu);
+                        self.agari_score.push(agari.score);
+                    }
+                }
+            }
+        }
+
         if !cans.can_act() {
             return;
         }
@@ -453,6 +615,14 @@ impl Gameplay {
         self.apply_gamma.push(label <= 37);
         self.at_turns.push(ctx.state.at_turn());
         self.shantens.push(ctx.state.shanten());
+        // ukeire is only meaningful at discard/draw decisions; kan-select
+        // entries share the preceding hand and would double-count.
+        let ukeire = if at_kan_select || label > 36 {
+            0
+        } else {
+            calc_ukeire(ctx.state, label)
+        };
+        self.ukeire.push(ukeire);
 
         if let Some(invisibles) = ctx.invisibles {
             let invisible_obs = invisibles[ctx.kyoku_idx].encode(
@@ -465,3 +635,291 @@ impl Gameplay {
         }
     }
 }
+
+/// Metadata describing one on-disk shard produced by
+/// [`GameplayLoader::load_gz_log_files_to_npz`].
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct ShardMeta {
+    /// Shard id; its `.npy` files are named `shard_{id:05}_{field}.npy`.
+    #[pyo3(get)]
+    pub shard_id: usize,
+    /// Number of move rows stored in the shard.
+    #[pyo3(get)]
+    pub num_rows: usize,
+    /// Row index (within the shard) at which each kyoku starts.
+    #[pyo3(get)]
+    pub kyoku_offsets: Vec<usize>,
+}
+
+#[pymethods]
+impl ShardMeta {
+    fn __repr__(&self) -> String {
+        format!(
+            "ShardMeta(shard_id={}, num_rows={}, kyoku_offsets={:?})",
+            self.shard_id, self.num_rows, self.kyoku_offsets,
+        )
+    }
+}
+
+/// Thread-local accumulator that buffers encoded rows and flushes them to a
+/// `.npy` shard once it crosses `shard_size`. Games are never split across
+/// shards: the threshold is checked after a whole game is appended, so each
+/// kyoku stays contiguous and peak memory stays O(`shard_size` + one game).
+#[derive(Default)]
+struct ShardWriter {
+    oracle: bool,
+    obs: Vec<Array2<f32>>,
+    invisible_obs: Vec<Array2<f32>>,
+    actions: Vec<i64>,
+    masks: Vec<Array1<bool>>,
+    shantens: Vec<i8>,
+    dones: Vec<bool>,
+    kyoku_offsets: Vec<usize>,
+    metas: Vec<ShardMeta>,
+}
+
+impl ShardWriter {
+    fn new(oracle: bool) -> Self {
+        Self {
+            oracle,
+            ..Default::default()
+        }
+    }
+
+    /// Consume one game's encoded rows into the shard buffer, moving the arrays
+    /// rather than copying them so peak memory stays O(`shard_size` + one game).
+    fn append(
+        &mut self,
+        mut game: Gameplay,
+        counter: &AtomicUsize,
+        out_dir: &str,
+        shard_size: usize,
+    ) -> Result<()> {
+        let dones = mem::take(&mut game.dones);
+        let mut obs = mem::take(&mut game.obs).into_iter();
+        let mut invisible_obs = mem::take(&mut game.invisible_obs).into_iter();
+        let mut masks = mem::take(&mut game.masks).into_iter();
+        let mut shantens = mem::take(&mut game.shantens).into_iter();
+
+        for (i, action) in mem::take(&mut game.actions).into_iter().enumerate() {
+            if i == 0 || dones[i - 1] {
+                self.kyoku_offsets.push(self.actions.len());
+            }
+            self.obs.push(obs.next().unwrap());
+            if self.oracle {
+                self.invisible_obs.push(invisible_obs.next().unwrap());
+            }
+            self.actions.push(action);
+            self.masks.push(masks.next().unwrap());
+            self.shantens.push(shantens.next().unwrap());
+            self.dones.push(dones[i]);
+        }
+        if self.actions.len() >= shard_size {
+            self.flush(counter, out_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the remaining buffered rows, if any, at end of the worker's input.
+    fn finish(&mut self, counter: &AtomicUsize, out_dir: &str) -> Result<()> {
+        if !self.actions.is_empty() {
+            self.flush(counter, out_dir)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, counter: &AtomicUsize, out_dir: &str) -> Result<()> {
+        let shard_id = counter.fetch_add(1, Ordering::Relaxed);
+        let num_rows = self.actions.len();
+
+        write_npy(out_dir, shard_id, "obs", &stack_owned(&self.obs)?)?;
+        if self.oracle {
+            write_npy(
+                out_dir,
+                shard_id,
+                "invisible_obs",
+                &stack_owned(&self.invisible_obs)?,
+            )?;
+        }
+        write_npy(out_dir, shard_id, "actions", &Array1::from(mem::take(&mut self.actions)))?;
+        write_npy(out_dir, shard_id, "masks", &stack_owned(&self.masks)?)?;
+        write_npy(out_dir, shard_id, "shantens", &Array1::from(mem::take(&mut self.shantens)))?;
+        write_npy(out_dir, shard_id, "dones", &Array1::from(mem::take(&mut self.dones)))?;
+
+        self.metas.push(ShardMeta {
+            shard_id,
+            num_rows,
+            kyoku_offsets: mem::take(&mut self.kyoku_offsets),
+        });
+
+        // `actions`/`shantens`/`dones` were drained via `mem::take`; clear the rest.
+        self.obs.clear();
+        self.invisible_obs.clear();
+        self.masks.clear();
+        Ok(())
+    }
+}
+
+/// Stack a slice of per-row arrays along a new leading axis.
+fn stack_owned<A, D>(rows: &[Array<A, D>]) -> Result<Array<A, D::Larger>>
+where
+    A: Clone,
+    D: Dimension,
+    D::Larger: Dimension,
+{
+    let views: Vec<_> = rows.iter().map(|a| a.view()).collect();
+    ndarray::stack(Axis(0), &views).context("failed to stack shard rows")
+}
+
+fn write_npy<A, D>(out_dir: &str, shard_id: usize, field: &str, arr: &Array<A, D>) -> Result<()>
+where
+    A: ndarray_npy::WritableElement,
+    D: Dimension,
+{
+    let path = format!("{out_dir}/shard_{shard_id:05}_{field}.npy");
+    let file = File::create(&path).with_context(|| format!("failed to create {path}"))?;
+    arr.write_npy(BufWriter::new(file))
+        .with_context(|| format!("failed to write {path}"))?;
+    Ok(())
+}
+
+fn ensure_dir(out_dir: &str) -> Result<()> {
+    fs::create_dir_all(out_dir).with_context(|| format!("failed to create {out_dir}"))
+}
+
+/// Assemble a [`WinningHand`] from the POV player's reconstructed state at the
+/// moment of its `Event::Hora`. Always returns `Some`; the hand is not checked
+/// here. A hand that does not decompose (e.g. from a malformed log) makes
+/// [`WinningHand::best`] return `None`, and the kyoku then gets no label.
+///
+/// `winning_tile` is the winning tile exactly as it appears in the `Hora`
+/// event, red-five flag included, so aka dora can be counted.
+fn build_winning_hand(state: &PlayerState, is_tsumo: bool, winning_tile: Tile) -> Option<WinningHand> {
+    use crate::state::Meld;
+
+    let winning_kind = winning_tile.deaka().as_usize();
+    let mut tehai = *state.tehai();
+    if !is_tsumo {
+        // A ron tile lives on the discard, not in the concealed hand yet.
+        tehai[winning_kind] += 1;
+    }
+
+    // Red fives: those concealed in hand, those in called melds, plus the
+    // winning tile itself when it is an aka five won by ron (a tsumo'd aka is
+    // already folded into the concealed count).
+    let mut aka_dora = state.akas_in_hand().iter().filter(|&&a| a).count() as u8;
+    for m in state.melds() {
+        let tiles: &[Tile] = match m {
+            Meld::Chi { pai, consumed } | Meld::Pon { pai, consumed } => {
+                aka_dora += pai.is_aka() as u8;
+                consumed
+            }
+            Meld::Kakan { pai, consumed } | Meld::Daiminkan { pai, consumed } => {
+                aka_dora += pai.is_aka() as u8;
+                consumed
+            }
+            Meld::Ankan { consumed } => consumed,
+        };
+        aka_dora += tiles.iter().filter(|t| t.is_aka()).count() as u8;
+    }
+    if !is_tsumo {
+        aka_dora += winning_tile.is_aka() as u8;
+    }
+
+    let melds = state
+        .melds()
+        .iter()
+        .map(|m| match m {
+            Meld::Chi { pai, consumed } => {
+                let mut kinds = [
+                    pai.deaka().as_usize(),
+                    consumed[0].deaka().as_usize(),
+                    consumed[1].deaka().as_usize(),
+                ];
+                kinds.sort_unstable();
+                agari::Meld::Chi { low: kinds[0] as u8 }
+            }
+            Meld::Pon { pai, .. } => agari::Meld::Pon {
+                tile: pai.deaka().as_usize() as u8,
+            },
+            Meld::Kakan { pai, .. } | Meld::Daiminkan { pai, .. } => agari::Meld::Minkan {
+                tile: pai.deaka().as_usize() as u8,
+            },
+            Meld::Ankan { consumed } => agari::Meld::Ankan {
+                tile: consumed[0].deaka().as_usize() as u8,
+            },
+        })
+        .collect();
+
+    let riichi = state.self_riichi_accepted();
+    Some(WinningHand {
+        tehai,
+        melds,
+        winning_tile: winning_kind as u8,
+        is_tsumo,
+        menzen: state.is_menzen(),
+        riichi,
+        ippatsu: riichi && state.is_ippatsu(),
+        round_wind: state.bakaze().as_usize() as u8,
+        seat_wind: state.jikaze().as_usize() as u8,
+        dora: state.dora_indicators().iter().map(|t| t.deaka().as_usize() as u8).collect(),
+        ura: if riichi {
+            state.ura_indicators().iter().map(|t| t.deaka().as_usize() as u8).collect()
+        } else {
+            vec![]
+        },
+        aka_dora,
+    })
+}
+
+/// Standard tile-acceptance (ukeire) count of the POV hand after `discard_kind`.
+///
+/// Acceptance is defined on the 3n+1 hand, so the tile being labelled for
+/// discard is removed from `ctx.state`'s post-draw (3n+2) hand first. A tile
+/// kind `t` is accepting when drawing it into that hand strictly lowers the
+/// regular shanten (normal form merged with chiitoitsu and kokushi). The
+/// returned value is the number of still-available copies summed over every
+/// accepting kind, counting only tiles unseen from this player's point of view
+/// (i.e. `4 - (in hand + discards + melds + dora indicators)`).
+///
+/// Returns 0 when the hand is already complete after the discard (shanten -1);
+/// honours never form ryanmen/kanchan partials so they only ever accept as
+/// pair/triplet waits, which the merged shanten calculation already handles.
+fn calc_ukeire(state: &PlayerState, discard_kind: usize) -> u16 {
+    // The action label keeps red fives distinct (34/35/36); de-redden to index
+    // the 34-kind concealed-hand array.
+    let discard_kind = match discard_kind {
+        34 => 4,
+        35 => 13,
+        36 => 22,
+        k => k,
+    };
+
+    let mut tehai = *state.tehai();
+    if tehai[discard_kind] == 0 {
+        return 0;
+    }
+    tehai[discard_kind] -= 1;
+    let tehai_len_div3 = (tehai.iter().map(|&c| c as usize).sum::<usize>() / 3) as u8;
+    let cur_shanten = shanten::calc_all(&tehai, tehai_len_div3);
+    if cur_shanten < 0 {
+        return 0;
+    }
+    let seen = state.tiles_seen();
+
+    let mut sum = 0;
+    for t in 0..34 {
+        // Cannot draw a fifth copy of a kind already held four times.
+        if tehai[t] >= 4 {
+            continue;
+        }
+        tehai[t] += 1;
+        let new_shanten = shanten::calc_all(&tehai, tehai_len_div3);
+        tehai[t] -= 1;
+        if new_shanten < cur_shanten {
+            sum += 4 - seen[t] as u16;
+        }
+    }
+    sum
+}
@@ -0,0 +1,464 @@
+//! Converter from Tenhou's native log format into mjai [`Event`]s.
+//!
+//! Tenhou stores a game as a stream of compact self-closing tags
+//! (`<INIT .../>`, `<T38/>`, `<D72/>`, `<N who="1" m="..."/>`, `<DORA .../>`,
+//! `<AGARI .../>`, `<RYUUKYOKU .../>`). We translate each into the same
+//! `Event` sequence the mjai pipeline already consumes, so every downstream
+//! tensor-extraction path is shared verbatim with the mjai loader.
+//!
+//! Tiles are encoded as 136 integer ids; `id / 4` is the 0..34 kind and the
+//! three red fives live at the fixed ids 16 / 52 / 88 (5m / 5p / 5s).
+
+use crate::mjai::Event;
+use crate::tile::Tile;
+use anyhow::{bail, ensure, Context, Result};
+use tinyvec::ArrayVec;
+
+/// The four draw tags, indexed by actor.
+const DRAW_TAGS: [char; 4] = ['T', 'U', 'V', 'W'];
+/// The four discard tags, indexed by actor.
+const DISCARD_TAGS: [char; 4] = ['D', 'E', 'F', 'G'];
+
+/// Parse a whole Tenhou mjlog document into mjai events.
+pub fn tenhou_to_mjai(raw: &str) -> Result<Vec<Event>> {
+    let mut events = vec![];
+    let mut names = [
+        String::new(),
+        String::new(),
+        String::new(),
+        String::new(),
+    ];
+    let mut last_draw = [None; 4];
+    let mut started = false;
+    // Defaults to aka games; the GO tag (which precedes UN) overrides it.
+    let mut aka_flag = true;
+
+    for tag in Tags::new(raw) {
+        let tag = tag?;
+        match tag.name {
+            "mjloggm" | "SHUFFLE" | "TAIKYOKU" | "BYE" => {}
+            "GO" => {
+                // Bit 0x02 of the ruleset type marks a no-red-dora game.
+                if let Some(rules) = tag.attr_u16("type") {
+                    aka_flag = rules & 0x02 == 0;
+                }
+            }
+            "UN" => {
+                for (i, key) in ["n0", "n1", "n2", "n3"].iter().enumerate() {
+                    if let Some(enc) = tag.attr(key) {
+                        names[i] = url_decode(enc);
+                    }
+                }
+                if !started {
+                    events.push(Event::StartGame {
+                        names: names.clone(),
+                        kyoku_first: 0,
+                        aka_flag,
+                    });
+                    started = true;
+                }
+            }
+            "INIT" => {
+                last_draw = [None; 4];
+                events.push(parse_init(&tag)?);
+            }
+            "N" => {
+                let who = tag.attr_u8("who").context("meld without actor")?;
+                let m = tag.attr_u16("m").context("meld without bitfield")?;
+                events.push(decode_meld(m, who)?);
+            }
+            "REACH" => {
+                let who = tag.attr_u8("who").context("reach without actor")?;
+                match tag.attr("step") {
+                    Some("1") => events.push(Event::Reach { actor: who }),
+                    Some("2") => events.push(Event::ReachAccepted { actor: who }),
+                    other => bail!("unknown reach step {other:?}"),
+                }
+            }
+            "DORA" => {
+                let id = tag.attr_u8("hai").context("dora without tile")?;
+                events.push(Event::Dora {
+                    dora_marker: tile_from_id(id)?,
+                });
+            }
+            "AGARI" => {
+                events.push(parse_agari(&tag)?);
+                events.push(Event::EndKyoku);
+                if tag.attr("owari").is_some() {
+                    events.push(Event::EndGame);
+                }
+            }
+            "RYUUKYOKU" => {
+                events.push(parse_ryuukyoku(&tag)?);
+                events.push(Event::EndKyoku);
+                if tag.attr("owari").is_some() {
+                    events.push(Event::EndGame);
+                }
+            }
+            name => {
+                // Draw / discard tags: a single letter followed by a tile id.
+                let mut chars = name.chars();
+                let lead = chars.next().unwrap_or(' ');
+                if let Some(actor) = DRAW_TAGS.iter().position(|&c| c == lead) {
+                    let id = name[1..].parse().context("bad draw tile")?;
+                    last_draw[actor] = Some(id);
+                    events.push(Event::Tsumo {
+                        actor: actor as u8,
+                        pai: tile_from_id(id)?,
+                    });
+                } else if let Some(actor) = DISCARD_TAGS.iter().position(|&c| c == lead) {
+                    let id = name[1..].parse().context("bad discard tile")?;
+                    let tsumogiri = last_draw[actor] == Some(id);
+                    events.push(Event::Dahai {
+                        actor: actor as u8,
+                        pai: tile_from_id(id)?,
+                        tsumogiri,
+                    });
+                } else {
+                    bail!("unexpected tag <{name}>");
+                }
+            }
+        }
+    }
+
+    ensure!(started, "log has no UN record");
+    Ok(events)
+}
+
+fn parse_init(tag: &Tag<'_>) -> Result<Event> {
+    let seed = tag.attr("seed").context("init without seed")?;
+    let seed: Vec<u32> = seed
+        .split(',')
+        .map(|s| s.parse())
+        .collect::<Result<_, _>>()
+        .context("bad seed")?;
+    ensure!(seed.len() == 6, "seed must have 6 fields");
+    let round = seed[0] as u8;
+
+    let scores: [i32; 4] = tag
+        .attr("ten")
+        .context("init without ten")?
+        .split(',')
+        .map(|s| s.parse::<i32>().map(|v| v * 100))
+        .collect::<Result<Vec<_>, _>>()?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("ten must have 4 fields"))?;
+
+    let mut tehais = [[Tile::default(); 13]; 4];
+    for (i, key) in ["hai0", "hai1", "hai2", "hai3"].iter().enumerate() {
+        let hand = tag.attr(key).with_context(|| format!("init without {key}"))?;
+        for (j, id) in hand.split(',').enumerate() {
+            tehais[i][j] = tile_from_id(id.parse()?)?;
+        }
+    }
+
+    Ok(Event::StartKyoku {
+        bakaze: Tile::try_from(27 + round / 4)?,
+        kyoku: round % 4 + 1,
+        honba: seed[1] as u8,
+        kyotaku: seed[2] as u8,
+        oya: tag.attr_u8("oya").context("init without oya")?,
+        scores,
+        dora_marker: tile_from_id(seed[5] as u8)?,
+        tehais,
+    })
+}
+
+fn parse_agari(tag: &Tag<'_>) -> Result<Event> {
+    let actor = tag.attr_u8("who").context("agari without who")?;
+    let target = tag.attr_u8("fromWho").context("agari without fromWho")?;
+    let machi = tag.attr_u8("machi").context("agari without machi")?;
+    Ok(Event::Hora {
+        actor,
+        target,
+        pai: tile_from_id(machi)?,
+        deltas: parse_sc(tag.attr("sc"))?,
+        ura_markers: parse_indicators(tag.attr("doraHaiUra"))?,
+    })
+}
+
+fn parse_ryuukyoku(tag: &Tag<'_>) -> Result<Event> {
+    Ok(Event::Ryukyoku {
+        deltas: parse_sc(tag.attr("sc"))?,
+    })
+}
+
+/// Decode Tenhou's `sc` attribute (four `before,delta` pairs in hundreds of
+/// points) into per-seat point deltas. Absent on malformed records.
+fn parse_sc(sc: Option<&str>) -> Result<Option<[i32; 4]>> {
+    let Some(sc) = sc else {
+        return Ok(None);
+    };
+    let vals: Vec<i32> = sc
+        .split(',')
+        .map(|s| s.parse())
+        .collect::<Result<_, _>>()
+        .context("bad sc field")?;
+    ensure!(vals.len() == 8, "sc must have 8 fields");
+    Ok(Some([
+        vals[1] * 100,
+        vals[3] * 100,
+        vals[5] * 100,
+        vals[7] * 100,
+    ]))
+}
+
+/// Decode a comma-separated list of 136-tile ids (e.g. `doraHaiUra`) into
+/// indicator tiles. Returns `None` when the attribute is absent.
+fn parse_indicators(list: Option<&str>) -> Result<Option<Vec<Tile>>> {
+    let Some(list) = list else {
+        return Ok(None);
+    };
+    let tiles = list
+        .split(',')
+        .map(|id| tile_from_id(id.parse().context("bad indicator id")?))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(tiles))
+}
+
+/// Decode Tenhou's 16-bit meld bitfield `m` into the corresponding call.
+///
+/// The low two bits encode who the tile was taken from, relative to `who`;
+/// the higher bits select the base tile and, for chi, which of the three
+/// tiles was the called one. See any mjlog reference for the layout.
+fn decode_meld(m: u16, who: u8) -> Result<Event> {
+    let from = (who + (m & 0x3) as u8) % 4;
+
+    if m & 0x4 != 0 {
+        // Chi: three consecutive tiles in one suit.
+        let t = (m >> 10) & 0x3f;
+        let called_in_run = (t % 3) as usize;
+        let base_seq = t / 3;
+        let base_kind = (base_seq / 7) * 9 + base_seq % 7;
+        let offsets = [(m >> 3) & 0x3, (m >> 5) & 0x3, (m >> 7) & 0x3];
+
+        let mut tiles = [Tile::default(); 3];
+        for i in 0..3 {
+            let id = (base_kind as u16 + i as u16) * 4 + offsets[i];
+            tiles[i] = tile_from_id(id as u8)?;
+        }
+        let pai = tiles[called_in_run];
+        let consumed: ArrayVec<[Tile; 2]> = tiles
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != called_in_run)
+            .map(|(_, t)| t)
+            .collect();
+        return Ok(Event::Chi {
+            actor: who,
+            target: from,
+            pai,
+            consumed: consumed.into_inner(),
+        });
+    }
+
+    if m & 0x18 != 0 {
+        // Pon or added-kan (kakan).
+        let t = (m >> 9) & 0x7f;
+        let called_in_set = (t % 3) as usize;
+        let kind = (t / 3) as u8;
+        let unused = (m >> 5) & 0x3; // the copy left out of the pon
+        let ids: ArrayVec<[u8; 3]> = (0..4u8)
+            .filter(|&c| c as u16 != unused)
+            .map(|c| kind * 4 + c)
+            .take(3)
+            .collect();
+        let pai = tile_from_id(ids[called_in_set])?;
+        let consumed: ArrayVec<[Tile; 2]> = ids
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != called_in_set)
+            .map(|(_, id)| tile_from_id(id))
+            .collect::<Result<_>>()?;
+
+        return Ok(if m & 0x8 != 0 {
+            Event::Pon {
+                actor: who,
+                target: from,
+                pai,
+                consumed: consumed.into_inner(),
+            }
+        } else {
+            Event::Kakan {
+                actor: who,
+                pai,
+                consumed: consumed.into_inner(),
+            }
+        });
+    }
+
+    // Kan: concealed (from == who) or open (daiminkan).
+    let hai = (m >> 8) & 0xff;
+    let kind = (hai / 4) as u8;
+    let all: [Tile; 4] = [
+        tile_from_id(kind * 4)?,
+        tile_from_id(kind * 4 + 1)?,
+        tile_from_id(kind * 4 + 2)?,
+        tile_from_id(kind * 4 + 3)?,
+    ];
+    if from == who {
+        Ok(Event::Ankan {
+            actor: who,
+            consumed: all,
+        })
+    } else {
+        let pai = tile_from_id(hai as u8)?;
+        let consumed: ArrayVec<[Tile; 3]> =
+            all.into_iter().filter(|&t| t != pai).collect();
+        Ok(Event::Daiminkan {
+            actor: who,
+            target: from,
+            pai,
+            consumed: consumed.into_inner(),
+        })
+    }
+}
+
+/// Map a 136-tile id to an mjai [`Tile`], honouring the three red fives.
+fn tile_from_id(id: u8) -> Result<Tile> {
+    let kind = match id {
+        16 => 34, // red 5m
+        52 => 35, // red 5p
+        88 => 36, // red 5s
+        _ => id / 4,
+    };
+    Tile::try_from(kind).with_context(|| format!("invalid tile id {id}"))
+}
+
+/// Minimal percent-decoding for Tenhou's URL-encoded player names.
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Iterator over the self-closing tags of a Tenhou document.
+struct Tags<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tags<'a> {
+    fn new(raw: &'a str) -> Self {
+        Self { rest: raw }
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Result<Tag<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.rest.find('<')?;
+        let end = match self.rest[start..].find('>') {
+            Some(e) => start + e,
+            None => return Some(Err(anyhow::anyhow!("unterminated tag"))),
+        };
+        let body = self.rest[start + 1..end].trim_end_matches('/').trim();
+        self.rest = &self.rest[end + 1..];
+        if body.is_empty() || body.starts_with('?') || body.starts_with('/') {
+            return self.next();
+        }
+        Some(Ok(Tag::parse(body)))
+    }
+}
+
+/// A parsed tag: its name plus the raw attribute slice for lazy lookups.
+struct Tag<'a> {
+    name: &'a str,
+    attrs: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    fn parse(body: &'a str) -> Self {
+        match body.find(char::is_whitespace) {
+            Some(i) => Tag {
+                name: &body[..i],
+                attrs: body[i..].trim_start(),
+            },
+            None => Tag {
+                name: body,
+                attrs: "",
+            },
+        }
+    }
+
+    fn attr(&self, key: &str) -> Option<&'a str> {
+        let mut rest = self.attrs;
+        while let Some(eq) = rest.find('=') {
+            let name = rest[..eq].trim();
+            let after = rest[eq + 1..].trim_start();
+            let after = after.strip_prefix('"')?;
+            let close = after.find('"')?;
+            let value = &after[..close];
+            if name == key {
+                return Some(value);
+            }
+            rest = &after[close + 1..];
+        }
+        None
+    }
+
+    fn attr_u8(&self, key: &str) -> Option<u8> {
+        self.attr(key)?.parse().ok()
+    }
+
+    fn attr_u16(&self, key: &str) -> Option<u16> {
+        self.attr(key)?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sc_decodes_to_point_deltas() {
+        // before/delta pairs in hundreds: winner +12000, loser -12000.
+        let deltas = parse_sc(Some("250,120,250,-120,250,0,250,0")).unwrap();
+        assert_eq!(deltas, Some([12000, -12000, 0, 0]));
+        assert_eq!(parse_sc(None).unwrap(), None);
+        assert!(parse_sc(Some("1,2,3")).is_err());
+    }
+
+    #[test]
+    fn aka_fives_map_to_their_own_kinds() {
+        assert_eq!(tile_from_id(16).unwrap().as_usize(), 34); // red 5m
+        assert_eq!(tile_from_id(52).unwrap().as_usize(), 35); // red 5p
+        assert_eq!(tile_from_id(88).unwrap().as_usize(), 36); // red 5s
+        assert_eq!(tile_from_id(17).unwrap().as_usize(), 4); // ordinary 5m
+    }
+
+    #[test]
+    fn go_tag_sets_aka_flag() {
+        // type bit 0x02 clear -> aka game; set -> no red fives.
+        let aka = tenhou_to_mjai(r#"<GO type="169"/><UN n0="A" n1="B" n2="C" n3="D"/>"#).unwrap();
+        let no_aka = tenhou_to_mjai(r#"<GO type="171"/><UN n0="A" n1="B" n2="C" n3="D"/>"#).unwrap();
+        assert!(matches!(aka[0], Event::StartGame { aka_flag: true, .. }));
+        assert!(matches!(no_aka[0], Event::StartGame { aka_flag: false, .. }));
+    }
+
+    #[test]
+    fn pon_meld_decodes_actor_and_target() {
+        // who=1, m bitfield for a pon of 1m taken from the next seat
+        // (relative offset 1 in the low bits), i.e. seat 2.
+        let ev = decode_meld(0x0009, 1).unwrap();
+        match ev {
+            Event::Pon { actor, target, .. } => {
+                assert_eq!(actor, 1);
+                assert_eq!(target, 2);
+            }
+            other => panic!("expected pon, got {other:?}"),
+        }
+    }
+}